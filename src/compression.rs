@@ -0,0 +1,24 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+
+use crate::mime::InputCompression;
+
+/// Opens `path` (or stdin for `-`) and, if `compression` names a codec, wraps
+/// the reader in the matching streaming decoder so callers never see
+/// compressed bytes.
+pub(crate) fn open(path: &Path, compression: InputCompression) -> Box<dyn Read> {
+    let reader: Box<dyn Read> = if path == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path).unwrap())
+    };
+
+    match compression {
+        InputCompression::None => reader,
+        InputCompression::Gzip => Box::new(GzDecoder::new(reader)),
+        InputCompression::Zstd => Box::new(zstd::Decoder::new(reader).unwrap()),
+    }
+}
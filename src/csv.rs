@@ -1,28 +1,92 @@
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::Read;
 use std::mem;
 use std::path::{Path, PathBuf};
 
-use csv::{ByteRecord, WriterBuilder};
+use csv::{ByteRecord, ReaderBuilder, WriterBuilder};
+use indicatif::ProgressBar;
+use memmap2::Mmap;
+use serde_json::{Map, Value};
 
-pub struct CsvChunker {
-    pub(crate) reader: csv::Reader<Box<dyn Read>>,
+use crate::byte_count::ByteCount;
+use crate::mime::InputCompression;
+use crate::util::truncate_snippet;
+
+/// The underlying source of CSV bytes. A plain, non-empty file is memory-mapped
+/// so the OS pages data in on demand instead of paying per-read syscall overhead
+/// on the large files this tool targets; stdin, compressed input and empty files
+/// are read through a boxed reader instead.
+pub(crate) enum CsvReader {
+    Mmap { reader: csv::Reader<&'static [u8]>, _mmap: Mmap },
+    Boxed(csv::Reader<Box<dyn Read>>),
+}
+
+impl CsvReader {
+    fn open(path: PathBuf, delimiter: u8, compression: InputCompression) -> Self {
+        // A compressed file must be decoded through a streaming reader, so only a
+        // plain file on disk takes the memory-mapped fast path.
+        if path == Path::new("-") || compression != InputCompression::None {
+            let reader = crate::compression::open(&path, compression);
+            CsvReader::Boxed(ReaderBuilder::new().delimiter(delimiter).from_reader(reader))
+        } else {
+            let file = File::open(path).unwrap();
+            // mmap(2) rejects zero-length mappings, so an empty file falls back to
+            // the boxed reader path the same as stdin/compressed input.
+            if file.metadata().unwrap().len() == 0 {
+                let reader: Box<dyn Read> = Box::new(file);
+                return CsvReader::Boxed(ReaderBuilder::new().delimiter(delimiter).from_reader(reader));
+            }
+            let mmap = unsafe { Mmap::map(&file).unwrap() };
+            // SAFETY: `_mmap` is declared after `reader` in this variant, so it is
+            // dropped after the reader that borrows from it (fields are dropped in
+            // declaration order), keeping the mapped bytes valid for the reader's
+            // whole lifetime even though `mmap` itself is moved into the struct.
+            let bytes: &'static [u8] = unsafe { mem::transmute(mmap.as_ref()) };
+            let reader = ReaderBuilder::new().delimiter(delimiter).from_reader(bytes);
+            CsvReader::Mmap { reader, _mmap: mmap }
+        }
+    }
+
+    fn byte_headers(&mut self) -> csv::Result<&ByteRecord> {
+        match self {
+            CsvReader::Mmap { reader, .. } => reader.byte_headers(),
+            CsvReader::Boxed(reader) => reader.byte_headers(),
+        }
+    }
+
+    fn read_byte_record(&mut self, record: &mut ByteRecord) -> csv::Result<bool> {
+        match self {
+            CsvReader::Mmap { reader, .. } => reader.read_byte_record(record),
+            CsvReader::Boxed(reader) => reader.read_byte_record(record),
+        }
+    }
+}
+
+pub struct CsvChunker<'a> {
+    pub(crate) reader: CsvReader,
     pub(crate) headers: ByteRecord,
     pub(crate) writer: csv::Writer<Vec<u8>>,
     pub(crate) record_count: usize,
     pub(crate) record: ByteRecord,
     pub(crate) size: usize,
     pub(crate) delimiter: u8,
+    skip_malformed: bool,
+    pb: &'a ProgressBar,
+    /// The number of malformed records skipped so far, only ever incremented
+    /// when `skip_malformed` is set.
+    pub skipped: usize,
 }
 
-impl CsvChunker {
-    pub fn new(path: PathBuf, size: usize, delimiter: u8) -> Self {
-        let reader = if path == Path::new("-") {
-            Box::new(io::stdin()) as Box<dyn Read>
-        } else {
-            Box::new(File::open(path).unwrap())
-        };
-        let mut reader = csv::Reader::from_reader(reader);
+impl<'a> CsvChunker<'a> {
+    pub fn new(
+        path: PathBuf,
+        size: usize,
+        delimiter: u8,
+        compression: InputCompression,
+        skip_malformed: bool,
+        pb: &'a ProgressBar,
+    ) -> Self {
+        let mut reader = CsvReader::open(path, delimiter, compression);
         let mut writer = WriterBuilder::new().delimiter(delimiter).from_writer(Vec::new());
         let headers = reader.byte_headers().unwrap().clone();
         writer.write_byte_record(&headers).unwrap();
@@ -34,33 +98,61 @@ impl CsvChunker {
             record: ByteRecord::new(),
             size,
             delimiter,
+            skip_malformed,
+            pb,
+            skipped: 0,
         }
     }
 }
 
-impl Iterator for CsvChunker {
-    type Item = Vec<u8>;
+impl<'a> Iterator for CsvChunker<'a> {
+    type Item = anyhow::Result<Vec<u8>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.reader.read_byte_record(&mut self.record).unwrap() {
-            self.writer.flush().unwrap();
-            if self.writer.get_ref().len() + self.record.len() >= self.size {
-                let mut writer =
-                    WriterBuilder::new().delimiter(self.delimiter).from_writer(Vec::new());
-                writer.write_byte_record(&self.headers).unwrap();
-                self.record_count = 0;
-                let writer = mem::replace(&mut self.writer, writer);
-
-                // Insert the header and out of bound record
-                self.writer.write_byte_record(&self.headers).unwrap();
-                self.writer.write_byte_record(&self.record).unwrap();
-                self.record_count += 1;
-
-                return Some(writer.into_inner().unwrap());
-            } else {
-                // Insert only the record
-                self.writer.write_byte_record(&self.record).unwrap();
-                self.record_count += 1;
+        loop {
+            match self.reader.read_byte_record(&mut self.record) {
+                Ok(true) => {
+                    self.writer.flush().unwrap();
+                    if self.writer.get_ref().len() + self.record.len() >= self.size {
+                        let mut writer =
+                            WriterBuilder::new().delimiter(self.delimiter).from_writer(Vec::new());
+                        writer.write_byte_record(&self.headers).unwrap();
+                        self.record_count = 0;
+                        let writer = mem::replace(&mut self.writer, writer);
+
+                        // Insert the header and out of bound record
+                        self.writer.write_byte_record(&self.headers).unwrap();
+                        self.writer.write_byte_record(&self.record).unwrap();
+                        self.record_count += 1;
+
+                        return Some(Ok(writer.into_inner().unwrap()));
+                    } else {
+                        // Insert only the record
+                        self.writer.write_byte_record(&self.record).unwrap();
+                        self.record_count += 1;
+                    }
+                }
+                Ok(false) => break,
+                Err(e) => {
+                    let position = e.position().map(|p| p.line());
+                    let snippet = truncate_snippet(&e.to_string(), 100);
+                    if self.skip_malformed {
+                        self.skipped += 1;
+                        match position {
+                            Some(line) => {
+                                self.pb.println(format!("Skipping malformed CSV record at line {line}: {snippet}"))
+                            }
+                            None => self.pb.println(format!("Skipping malformed CSV record: {snippet}")),
+                        }
+                        continue;
+                    }
+                    return Some(Err(match position {
+                        Some(line) => {
+                            anyhow::anyhow!("malformed CSV record at line {line}: {snippet}")
+                        }
+                        None => anyhow::anyhow!("malformed CSV record: {snippet}"),
+                    }));
+                }
             }
         }
         if self.record_count == 0 {
@@ -72,7 +164,177 @@ impl Iterator for CsvChunker {
             // We make the buffer empty by doing that and next time we will
             // come back to this _if else_ condition to then return None.
             let writer = mem::replace(&mut self.writer, writer);
-            Some(writer.into_inner().unwrap())
+            Some(Ok(writer.into_inner().unwrap()))
+        }
+    }
+}
+
+/// A CSV column's type, taken from a Meilisearch-style typed header
+/// (`price:number`, `in_stock:boolean`, or a plain `title` defaulting to string).
+enum ColumnType {
+    Number,
+    Boolean,
+    String,
+}
+
+fn parse_columns(headers: &ByteRecord) -> Vec<(String, ColumnType)> {
+    headers
+        .iter()
+        .map(|header| {
+            let header = String::from_utf8_lossy(header);
+            match header.rsplit_once(':') {
+                Some((field, "number")) => (field.to_owned(), ColumnType::Number),
+                Some((field, "boolean")) => (field.to_owned(), ColumnType::Boolean),
+                _ => (header.into_owned(), ColumnType::String),
+            }
+        })
+        .collect()
+}
+
+/// Parses a single cell according to its column's declared type. An empty cell
+/// becomes an absent field (`None`) rather than a false-y default like `0` or
+/// `false`, except for plain string columns where it is a legitimate empty string.
+fn parse_cell(column: &ColumnType, cell: &[u8], row: u64) -> anyhow::Result<Option<Value>> {
+    if cell.is_empty() {
+        return Ok(match column {
+            ColumnType::String => Some(Value::String(String::new())),
+            ColumnType::Number | ColumnType::Boolean => None,
+        });
+    }
+
+    let text = std::str::from_utf8(cell)
+        .map_err(|_| anyhow::anyhow!("row {row}: cell is not valid UTF-8"))?;
+
+    match column {
+        ColumnType::String => Ok(Some(Value::String(text.to_owned()))),
+        ColumnType::Number => match text.parse::<i64>() {
+            Ok(n) => Ok(Some(Value::Number(n.into()))),
+            Err(_) => match text.parse::<f64>().ok().and_then(serde_json::Number::from_f64) {
+                Some(n) => Ok(Some(Value::Number(n))),
+                None => anyhow::bail!("row {row}: {text:?} is not a valid number"),
+            },
+        },
+        ColumnType::Boolean => match text {
+            "true" => Ok(Some(Value::Bool(true))),
+            "false" => Ok(Some(Value::Bool(false))),
+            _ => anyhow::bail!("row {row}: {text:?} is not a valid boolean"),
+        },
+    }
+}
+
+/// Reads a CSV file with Meilisearch-style typed headers (`field:number`,
+/// `field:boolean`, plain `field` as string) and coerces each row into a typed
+/// JSON document locally, yielding size-bounded NDJSON chunks. This lets users
+/// get deterministic typing and early failure on bad values instead of relying
+/// on server-side guessing.
+pub struct TypedCsvChunker<'a> {
+    reader: CsvReader,
+    columns: Vec<(String, ColumnType)>,
+    record: ByteRecord,
+    buffer: Vec<u8>,
+    size: usize,
+    row: u64,
+    skip_malformed: bool,
+    pb: &'a ProgressBar,
+    /// The number of malformed records skipped so far, only ever incremented
+    /// when `skip_malformed` is set.
+    pub skipped: usize,
+}
+
+impl<'a> TypedCsvChunker<'a> {
+    pub fn new(
+        path: PathBuf,
+        size: usize,
+        delimiter: u8,
+        compression: InputCompression,
+        skip_malformed: bool,
+        pb: &'a ProgressBar,
+    ) -> Self {
+        let mut reader = CsvReader::open(path, delimiter, compression);
+        let columns = parse_columns(reader.byte_headers().unwrap());
+        Self {
+            reader,
+            columns,
+            record: ByteRecord::new(),
+            buffer: Vec::new(),
+            size,
+            row: 0,
+            skip_malformed,
+            pb,
+            skipped: 0,
+        }
+    }
+
+    fn parse_record(&mut self) -> anyhow::Result<Map<String, Value>> {
+        let mut object = Map::new();
+        for ((field, column), cell) in self.columns.iter().zip(self.record.iter()) {
+            if let Some(value) = parse_cell(column, cell, self.row)? {
+                object.insert(field.clone(), value);
+            }
+        }
+        Ok(object)
+    }
+}
+
+impl<'a> Iterator for TypedCsvChunker<'a> {
+    type Item = anyhow::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.reader.read_byte_record(&mut self.record) {
+                Ok(true) => {
+                    self.row += 1;
+                    let object = match self.parse_record() {
+                        Ok(object) => object,
+                        Err(e) => {
+                            let snippet = truncate_snippet(&e.to_string(), 100);
+                            if self.skip_malformed {
+                                self.skipped += 1;
+                                self.pb.println(format!("Skipping malformed CSV record: {snippet}"));
+                                continue;
+                            }
+                            return Some(Err(anyhow::anyhow!("malformed CSV record: {snippet}")));
+                        }
+                    };
+
+                    let mut counter = ByteCount::new();
+                    serde_json::to_writer(&mut counter, &object).unwrap();
+
+                    if !self.buffer.is_empty() && self.buffer.len() + counter.count() >= self.size {
+                        let buffer = mem::take(&mut self.buffer);
+                        serde_json::to_writer(&mut self.buffer, &object).unwrap();
+                        return Some(Ok(buffer));
+                    } else {
+                        serde_json::to_writer(&mut self.buffer, &object).unwrap();
+                    }
+                }
+                Ok(false) => break,
+                Err(e) => {
+                    let position = e.position().map(|p| p.line());
+                    let snippet = truncate_snippet(&e.to_string(), 100);
+                    if self.skip_malformed {
+                        self.skipped += 1;
+                        match position {
+                            Some(line) => {
+                                self.pb.println(format!("Skipping malformed CSV record at line {line}: {snippet}"))
+                            }
+                            None => self.pb.println(format!("Skipping malformed CSV record: {snippet}")),
+                        }
+                        continue;
+                    }
+                    return Some(Err(match position {
+                        Some(line) => {
+                            anyhow::anyhow!("malformed CSV record at line {line}: {snippet}")
+                        }
+                        None => anyhow::anyhow!("malformed CSV record: {snippet}"),
+                    }));
+                }
+            }
+        }
+        if self.buffer.is_empty() {
+            None
+        } else {
+            Some(Ok(mem::take(&mut self.buffer)))
         }
     }
 }
@@ -0,0 +1,149 @@
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+
+use indicatif::ProgressBar;
+use serde::de::{SeqAccess, Visitor};
+use serde_json::{to_writer, Deserializer, Map, Value};
+
+use crate::byte_count::ByteCount;
+use crate::mime::InputCompression;
+use crate::util::truncate_snippet;
+
+/// Streams a top-level JSON array and yields size-bounded chunks, each one
+/// re-serialized as its own valid JSON array.
+///
+/// A JSON array cannot be driven element by element the way a whitespace or
+/// newline separated stream can, so the file is parsed on a background
+/// thread with a custom [`Visitor`] that walks the array's [`SeqAccess`] and
+/// sends completed chunks back over a channel as soon as they are full.
+pub struct JsonChunker {
+    rx: Receiver<anyhow::Result<Vec<u8>>>,
+    skipped: Arc<AtomicUsize>,
+}
+
+impl JsonChunker {
+    pub fn new(
+        path: PathBuf,
+        size: usize,
+        compression: InputCompression,
+        skip_malformed: bool,
+        pb: ProgressBar,
+    ) -> Self {
+        let (tx, rx) = mpsc::sync_channel(100);
+        let skipped = Arc::new(AtomicUsize::new(0));
+        let visitor_skipped = Arc::clone(&skipped);
+        thread::spawn(move || {
+            let reader = crate::compression::open(&path, compression);
+            let reader = BufReader::new(reader);
+            let error_tx = tx.clone();
+            let visitor = ChunkVisitor {
+                tx,
+                size,
+                buffer: Vec::new(),
+                count: 0,
+                skip_malformed,
+                pb,
+                skipped: visitor_skipped,
+            };
+            // A non-object element is reported by the visitor itself (see
+            // `visit_seq`); a genuine JSON syntax error (truncated file, bad
+            // token, bad escape) instead surfaces here as an `Err` from
+            // `deserialize_seq`, which we forward over the channel the same
+            // way so the consumer doesn't mistake a corrupted file for a
+            // clean end of stream.
+            if let Err(e) = Deserializer::from_reader(reader).deserialize_seq(visitor) {
+                let _ = error_tx.send(Err(anyhow::anyhow!("malformed JSON input: {e}")));
+            }
+        });
+        Self { rx, skipped }
+    }
+
+    /// The number of malformed array elements skipped so far, only ever
+    /// incremented when `skip_malformed` is set.
+    pub fn skipped(&self) -> usize {
+        self.skipped.load(Ordering::Relaxed)
+    }
+}
+
+impl Iterator for JsonChunker {
+    type Item = anyhow::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok()
+    }
+}
+
+struct ChunkVisitor {
+    tx: SyncSender<anyhow::Result<Vec<u8>>>,
+    size: usize,
+    buffer: Vec<u8>,
+    count: usize,
+    skip_malformed: bool,
+    pb: ProgressBar,
+    skipped: Arc<AtomicUsize>,
+}
+
+impl ChunkVisitor {
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.buffer.push(b']');
+            let chunk = std::mem::take(&mut self.buffer);
+            let _ = self.tx.send(Ok(chunk));
+            self.count = 0;
+        }
+    }
+
+    fn push(&mut self, object: &Map<String, Value>) {
+        let mut counter = ByteCount::new();
+        to_writer(&mut counter, object).unwrap();
+
+        // Closing bracket and separator/opening bracket are both a single byte.
+        if self.count > 0 && self.buffer.len() + counter.count() + 1 >= self.size {
+            self.flush();
+        }
+
+        self.buffer.push(if self.count == 0 { b'[' } else { b',' });
+        to_writer(&mut self.buffer, object).unwrap();
+        self.count += 1;
+    }
+}
+
+impl<'de> Visitor<'de> for ChunkVisitor {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a top-level JSON array of objects")
+    }
+
+    fn visit_seq<A>(mut self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        // Elements are parsed as a generic `Value` first so a non-object
+        // element can be reported (and skipped) without losing our place in
+        // the underlying token stream, which a typed parse failure would.
+        while let Some(value) = seq.next_element::<Value>()? {
+            match value {
+                Value::Object(object) => self.push(&object),
+                other => {
+                    let snippet = truncate_snippet(&other.to_string(), 100);
+                    if self.skip_malformed {
+                        self.skipped.fetch_add(1, Ordering::Relaxed);
+                        self.pb.println(format!("Skipping malformed JSON element: {snippet}"));
+                    } else {
+                        let _ = self.tx.send(Err(anyhow::anyhow!(
+                            "malformed JSON element, expected an object: {snippet}"
+                        )));
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        self.flush();
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 use std::io::prelude::*;
 use std::num::NonZero;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::Receiver;
+use std::sync::Mutex;
 use std::time::Duration;
 use std::{fs, thread};
 
@@ -12,15 +14,18 @@ use exponential_backoff::Backoff;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use indicatif::{ProgressBar, ProgressStyle};
-use mime::Mime;
+use mime::{InputCompression, Mime};
 use rayon::iter::{ParallelBridge as _, ParallelIterator};
 use rayon::{ThreadPool, ThreadPoolBuilder};
 use ureq::{Agent, AgentBuilder};
 
 mod byte_count;
+mod compression;
 mod csv;
+mod json;
 mod mime;
 mod nd_json;
+mod util;
 
 /// A tool to import massive datasets into Meilisearch by sending them in batches.
 #[derive(Debug, Parser, Clone)]
@@ -51,10 +56,24 @@ struct Opt {
     #[structopt(long, default_value_t = b',')]
     csv_delimiter: u8,
 
+    /// Locally coerce Meilisearch's typed CSV headers (`field:number`, `field:boolean`, plain
+    /// `field` as string) into typed JSON documents before sending them as NDJSON, instead of
+    /// relying on Meilisearch's server-side type guessing.
+    #[structopt(long)]
+    csv_types: bool,
+
     /// Defines whether we send the embeddings to the remote server or do not send a single embedding.
     #[structopt(long)]
     ignore_embeddings: bool,
 
+    /// Skip malformed records instead of aborting the whole import.
+    ///
+    /// Each skipped record is logged, CSV and NDJSON records with their line or
+    /// row number when one is known and JSON array elements with a snippet only,
+    /// and a summary of how many were skipped is printed once the import finishes.
+    #[structopt(long)]
+    skip_malformed: bool,
+
     /// A list of file paths that are streamed and sent to Meilisearch in batches,
     /// where content can come from stdin using the special minus (-) path.
     #[structopt(long, num_args(1..))]
@@ -64,6 +83,11 @@ struct Opt {
     #[structopt(long)]
     format: Option<Mime>,
 
+    /// The compression codec the input is encoded with. Overrides auto-detection from the
+    /// file extension (e.g. `.csv.gz`, `.ndjson.zst`); required to decompress stdin input.
+    #[structopt(long)]
+    input_compression: Option<InputCompression>,
+
     /// The size of the batches sent to Meilisearch.
     #[structopt(long, default_value = "20 MiB")]
     batch_size: Byte,
@@ -79,6 +103,11 @@ struct Opt {
     #[structopt(long)]
     skip_batches: Option<u64>,
 
+    /// Wait for each batch's indexing task to finish and verify it succeeded, instead of
+    /// assuming success as soon as Meilisearch replies with a 202 Accepted.
+    #[structopt(long)]
+    wait: bool,
+
     /// Tells us to read data from stdin and to use the provided format.
     #[structopt(long, conflicts_with("files"))]
     stdin: Option<Mime>,
@@ -100,6 +129,10 @@ enum DocumentOperation {
     AddOrUpdate,
 }
 
+/// Sends a batch of data to Meilisearch and returns the `taskUid` of the
+/// resulting indexing task. A `2xx` response only means Meilisearch accepted
+/// the batch, not that indexing it succeeded; pass the returned `taskUid` to
+/// [`wait_for_task`] to check on that.
 fn send_data(
     opt: &Opt,
     agent: &Agent,
@@ -107,7 +140,7 @@ fn send_data(
     pb: &ProgressBar,
     mime: &Mime,
     data: &[u8],
-) -> anyhow::Result<()> {
+) -> anyhow::Result<u64> {
     let api_key = opt.api_key.clone();
     let mut url = format!("{}/indexes/{}/documents", opt.url, opt.index);
     if let Some(primary_key) = &opt.primary_key {
@@ -137,7 +170,10 @@ fn send_data(
         }
 
         match request.send_bytes(&data) {
-            Ok(response) if matches!(response.status(), 200..=299) => return Ok(()),
+            Ok(response) if matches!(response.status(), 200..=299) => {
+                let body: serde_json::Value = response.into_json()?;
+                return body["taskUid"].as_u64().context("Response did not contain a taskUid");
+            }
             Ok(response) => {
                 let e = response.into_string()?;
                 pb.println(format!("Attempt #{attempt}: {e}"));
@@ -153,6 +189,75 @@ fn send_data(
     anyhow::bail!("Too many errors. Stopping the retries.")
 }
 
+/// Why an indexing task did not succeed: the task's `error` when it failed, or a
+/// `timeout` when it never left the `enqueued`/`processing` states.
+struct TaskFailure {
+    code: String,
+    message: String,
+}
+
+/// Polls `GET {url}/tasks/{task_uid}` until the task reaches `succeeded` or `failed`,
+/// returning the task's `error` object (code + message) when it failed.
+///
+/// A transient transport error while polling is retried with the same backoff
+/// as a status check, and running out of retries (or the task never leaving
+/// `enqueued`/`processing`) is reported as a [`TaskFailure`] rather than a
+/// hard `Err`, so one flaky or slow batch doesn't cancel every other in-flight
+/// batch in `rx.into_iter().enumerate().par_bridge().try_for_each(...)`.
+fn wait_for_task(
+    opt: &Opt,
+    agent: &Agent,
+    pb: &ProgressBar,
+    task_uid: u64,
+) -> anyhow::Result<Option<TaskFailure>> {
+    let url = format!("{}/tasks/{}", opt.url, task_uid);
+
+    let retries = 50;
+    let min = Duration::from_millis(200);
+    let max = Duration::from_secs(30);
+    let backoff = Backoff::new(retries, min, max);
+
+    for (attempt, duration) in backoff.into_iter().enumerate() {
+        let mut request = agent.get(&url);
+        if let Some(api_key) = &opt.api_key {
+            request = request.set("Authorization", &format!("Bearer {}", api_key));
+        }
+
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(e) => {
+                pb.println(format!("Attempt #{attempt} waiting for task {task_uid}: {e}"));
+                thread::sleep(duration);
+                continue;
+            }
+        };
+        let body: serde_json::Value = match response.into_json() {
+            Ok(body) => body,
+            Err(e) => {
+                pb.println(format!("Attempt #{attempt} waiting for task {task_uid}: {e}"));
+                thread::sleep(duration);
+                continue;
+            }
+        };
+
+        match body["status"].as_str() {
+            Some("succeeded") => return Ok(None),
+            Some("failed") => {
+                let error = &body["error"];
+                let code = error["code"].as_str().unwrap_or("unknown_error").to_owned();
+                let message = error["message"].as_str().unwrap_or("no message").to_owned();
+                return Ok(Some(TaskFailure { code, message }));
+            }
+            _ => thread::sleep(duration),
+        }
+    }
+
+    Ok(Some(TaskFailure {
+        code: "timeout".to_owned(),
+        message: format!("timed out waiting for task {task_uid} to complete"),
+    }))
+}
+
 fn main() -> anyhow::Result<()> {
     let opt = Opt::parse();
     let agent = AgentBuilder::new().timeout(Duration::from_secs(30)).build();
@@ -178,13 +283,32 @@ fn main() -> anyhow::Result<()> {
             },
         };
 
+        // use the explicit compression override if provided, useful for stdin input, and
+        // otherwise detect the codec from the extension (looked past to find the mime type above).
+        let compression = opt
+            .input_compression
+            .or_else(|| {
+                (path != Path::new("-")).then(|| InputCompression::from_path(&path))
+            })
+            .unwrap_or(InputCompression::None);
+
         let pool = ThreadPoolBuilder::new().num_threads(opt.jobs.get()).build()?;
 
         if opt.ignore_embeddings && mime != Mime::NdJson {
             anyhow::bail!("Ignoring embeddings can only be used with NDJSON files");
         }
 
-        let file_size = if path == Path::new("-") { 0 } else { fs::metadata(&path)?.len() };
+        if opt.csv_types && mime != Mime::Csv {
+            anyhow::bail!("--csv-types can only be used with CSV files");
+        }
+
+        // The on-disk size of a compressed file is a poor estimate of the number of
+        // uncompressed batches it will produce, so fall back to a spinner for those.
+        let file_size = if path == Path::new("-") || compression != InputCompression::None {
+            0
+        } else {
+            fs::metadata(&path)?.len()
+        };
         let size = opt.batch_size.as_u64() as usize;
         let nb_chunks = file_size / size as u64;
         let pb = if file_size > 0 {
@@ -196,52 +320,104 @@ fn main() -> anyhow::Result<()> {
         };
         pb.inc(0);
 
-        match mime {
-            Mime::Json => {
-                if opt.skip_batches.zip(pb.length()).map_or(true, |(s, l)| s > l) {
-                    let data = fs::read_to_string(path)?;
-                    send_data(&opt, &agent, opt.upload_operation, &pb, &mime, data.as_bytes())?;
-                }
-                pb.inc(1);
-            }
-            Mime::NdJson => {
-                thread::scope(|s| {
-                    let (tx, rx) = std::sync::mpsc::sync_channel(100);
-                    let producer_handle = s.spawn(move || {
-                        for chunk in nd_json::NdJsonChunker::new(path, size, opt.ignore_embeddings) {
-                            tx.send(chunk)?;
+        // A plain reference (or, for `JsonChunker`, a cheap clone) is moved into the
+        // producer closure below instead of `pb` itself, so `pb` stays available to
+        // `send_producer_in_parallel` and the summary printed after the scope ends.
+        let pb_ref = &pb;
+
+        let skipped = match mime {
+            Mime::Json => thread::scope(|s| {
+                let (tx, rx) = std::sync::mpsc::sync_channel(100);
+                let chunker_pb = pb.clone();
+                let producer_handle = s.spawn(move || {
+                    let mut chunker =
+                        json::JsonChunker::new(path, size, compression, opt.skip_malformed, chunker_pb);
+                    for chunk in &mut chunker {
+                        tx.send(chunk?)?;
+                    }
+                    Ok(chunker.skipped()) as anyhow::Result<usize>
+                });
+
+                let sender_handle =
+                    s.spawn(|| send_producer_in_parallel(&opt, &agent, &pb, &pool, &mime, rx));
+
+                let skipped = producer_handle.join().unwrap()?;
+                sender_handle.join().unwrap()?;
+
+                Ok(skipped) as anyhow::Result<usize>
+            })?,
+            Mime::NdJson => thread::scope(|s| {
+                let (tx, rx) = std::sync::mpsc::sync_channel(100);
+                let producer_handle = s.spawn(move || {
+                    let mut chunker = nd_json::NdJsonChunker::new(
+                        path,
+                        size,
+                        opt.ignore_embeddings,
+                        compression,
+                        opt.skip_malformed,
+                        pb_ref,
+                    );
+                    for chunk in &mut chunker {
+                        tx.send(chunk?)?;
+                    }
+                    Ok(chunker.skipped) as anyhow::Result<usize>
+                });
+
+                let sender_handle =
+                    s.spawn(|| send_producer_in_parallel(&opt, &agent, &pb, &pool, &mime, rx));
+
+                let skipped = producer_handle.join().unwrap()?;
+                sender_handle.join().unwrap()?;
+
+                Ok(skipped) as anyhow::Result<usize>
+            })?,
+            Mime::Csv => thread::scope(|s| {
+                let (tx, rx) = std::sync::mpsc::sync_channel(100);
+                // Typed CSV documents are coerced into JSON objects locally, so they are
+                // sent as NDJSON rather than as a re-encoded CSV batch.
+                let send_mime = if opt.csv_types { Mime::NdJson } else { Mime::Csv };
+                let producer_handle = s.spawn(move || {
+                    if opt.csv_types {
+                        let mut chunker = csv::TypedCsvChunker::new(
+                            path,
+                            size,
+                            opt.csv_delimiter,
+                            compression,
+                            opt.skip_malformed,
+                            pb_ref,
+                        );
+                        for chunk in &mut chunker {
+                            tx.send(chunk?)?;
                         }
-                        Ok(()) as anyhow::Result<()>
-                    });
-
-                    let sender_handle =
-                        s.spawn(|| send_producer_in_parallel(&opt, &agent, &pb, &pool, &mime, rx));
-
-                    producer_handle.join().unwrap()?;
-                    sender_handle.join().unwrap()?;
-
-                    Ok(()) as anyhow::Result<()>
-                })?;
-            }
-            Mime::Csv => {
-                thread::scope(|s| {
-                    let (tx, rx) = std::sync::mpsc::sync_channel(100);
-                    let producer_handle = s.spawn(move || {
-                        for chunk in csv::CsvChunker::new(path, size, opt.csv_delimiter) {
-                            tx.send(chunk)?;
+                        Ok(chunker.skipped) as anyhow::Result<usize>
+                    } else {
+                        let mut chunker = csv::CsvChunker::new(
+                            path,
+                            size,
+                            opt.csv_delimiter,
+                            compression,
+                            opt.skip_malformed,
+                            pb_ref,
+                        );
+                        for chunk in &mut chunker {
+                            tx.send(chunk?)?;
                         }
-                        Ok(()) as anyhow::Result<()>
-                    });
+                        Ok(chunker.skipped) as anyhow::Result<usize>
+                    }
+                });
 
-                    let sender_handle =
-                        s.spawn(|| send_producer_in_parallel(&opt, &agent, &pb, &pool, &mime, rx));
+                let sender_handle = s
+                    .spawn(|| send_producer_in_parallel(&opt, &agent, &pb, &pool, &send_mime, rx));
 
-                    producer_handle.join().unwrap()?;
-                    sender_handle.join().unwrap()?;
+                let skipped = producer_handle.join().unwrap()?;
+                sender_handle.join().unwrap()?;
 
-                    Ok(()) as anyhow::Result<()>
-                })?;
-            }
+                Ok(skipped) as anyhow::Result<usize>
+            })?,
+        };
+
+        if skipped > 0 {
+            pb.println(format!("Skipped {skipped} malformed record(s)"));
         }
     }
 
@@ -256,13 +432,51 @@ fn send_producer_in_parallel(
     mime: &Mime,
     rx: Receiver<Vec<u8>>,
 ) -> anyhow::Result<()> {
+    let sent = AtomicU64::new(0);
+    let failed_tasks = Mutex::new(Vec::new());
+
     pool.install(|| {
-        rx.into_iter().par_bridge().try_for_each(|chunk| {
+        // The ordinal of each incoming chunk in the order it was pulled off `rx`,
+        // matching the same count `--skip-batches` is meant to resume from, so a
+        // reported failure can be mapped back to a `--skip-batches` value.
+        rx.into_iter().enumerate().par_bridge().try_for_each(|(batch, chunk)| {
             if opt.skip_batches.zip(pb.length()).map_or(true, |(s, l)| s > l) {
-                send_data(&opt, &agent, opt.upload_operation, &pb, &mime, &chunk)?;
+                sent.fetch_add(1, Ordering::SeqCst);
+                let task_uid = send_data(&opt, &agent, opt.upload_operation, &pb, &mime, &chunk)?;
+                if opt.wait {
+                    if let Some(failure) = wait_for_task(opt, agent, pb, task_uid)? {
+                        pb.println(format!(
+                            "Batch {batch} (task {task_uid}) failed: {} ({})",
+                            failure.message, failure.code
+                        ));
+                        failed_tasks.lock().unwrap().push((batch, task_uid));
+                    }
+                }
             }
             pb.inc(1);
             Ok(()) as anyhow::Result<()>
         })
-    })
+    })?;
+
+    let failed_tasks = failed_tasks.into_inner().unwrap();
+    let sent = sent.into_inner();
+    if opt.wait {
+        pb.println(format!(
+            "{}/{sent} batches succeeded indexing",
+            sent - failed_tasks.len() as u64
+        ));
+    }
+    if !failed_tasks.is_empty() {
+        anyhow::bail!(
+            "{} batch(es) failed to index: {}",
+            failed_tasks.len(),
+            failed_tasks
+                .iter()
+                .map(|(batch, task_uid)| format!("batch {batch} (task {task_uid})"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    Ok(())
 }
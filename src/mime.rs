@@ -9,15 +9,27 @@ pub enum Mime {
 }
 
 impl Mime {
-    pub fn from_path(path: &Path) -> Option<Mime> {
-        match path.extension().and_then(|ext| ext.to_str()) {
-            Some("json") => Some(Mime::Json),
-            Some("ndjson" | "jsonl") => Some(Mime::NdJson),
-            Some("csv") => Some(Mime::Csv),
+    fn from_extension(ext: &str) -> Option<Mime> {
+        match ext {
+            "json" => Some(Mime::Json),
+            "ndjson" | "jsonl" => Some(Mime::NdJson),
+            "csv" => Some(Mime::Csv),
             _ => None,
         }
     }
 
+    /// Detects the file format from `path`'s extension, looking past a
+    /// compression extension when there is one (e.g. `data.ndjson.gz` is
+    /// detected as [`Mime::NdJson`], the same as `data.ndjson`).
+    pub fn from_path(path: &Path) -> Option<Mime> {
+        let ext = path.extension().and_then(|ext| ext.to_str())?;
+        if InputCompression::from_extension(ext).is_some() {
+            Mime::from_path(Path::new(path.file_stem()?))
+        } else {
+            Mime::from_extension(ext)
+        }
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
             Mime::Json => "application/json",
@@ -41,3 +53,47 @@ impl FromStr for Mime {
         }
     }
 }
+
+/// The compression codec an input file is encoded with, detected from a
+/// compression extension layered on top of the format extension (`data.csv.gz`,
+/// `data.ndjson.zst`), or given explicitly for stdin via `--input-compression`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl InputCompression {
+    fn from_extension(ext: &str) -> Option<InputCompression> {
+        match ext {
+            "gz" => Some(InputCompression::Gzip),
+            "zst" => Some(InputCompression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Detects the compression codec from `path`'s extension, or `None` when
+    /// there isn't a recognized one.
+    pub fn from_path(path: &Path) -> InputCompression {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(InputCompression::from_extension)
+            .unwrap_or(InputCompression::None)
+    }
+}
+
+impl FromStr for InputCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(InputCompression::None),
+            "gzip" | "gz" => Ok(InputCompression::Gzip),
+            "zstd" | "zst" => Ok(InputCompression::Zstd),
+            otherwise => anyhow::bail!(
+                "unknown {otherwise} input compression. Possible values are none, gzip, and zstd."
+            ),
+        }
+    }
+}
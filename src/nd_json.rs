@@ -1,44 +1,107 @@
 use std::fs::File;
 use std::io::{BufReader, Read};
+use std::mem;
 use std::path::{Path, PathBuf};
-use std::{io, mem};
 
-use serde_json::de::IoRead;
+use indicatif::ProgressBar;
+use memmap2::Mmap;
+use serde_json::de::{IoRead, SliceRead};
 use serde_json::{to_writer, Deserializer, Map, StreamDeserializer, Value};
 
 use crate::byte_count::ByteCount;
+use crate::mime::InputCompression;
+use crate::util::truncate_snippet;
 
-pub struct NdJsonChunker {
-    #[allow(clippy::type_complexity)]
-    pub reader: StreamDeserializer<'static, IoRead<BufReader<Box<dyn Read>>>, Map<String, Value>>,
+/// The underlying source of NDJSON bytes. A plain, non-empty file is
+/// memory-mapped so the OS pages data in on demand instead of paying per-read
+/// syscall overhead on the large files this tool targets; stdin, compressed
+/// input and empty files are read through a boxed reader instead.
+enum JsonSource {
+    Mmap { reader: StreamDeserializer<'static, SliceRead<'static>, Map<String, Value>>, _mmap: Mmap },
+    Boxed(StreamDeserializer<'static, IoRead<BufReader<Box<dyn Read>>>, Map<String, Value>>),
+}
+
+impl Iterator for JsonSource {
+    type Item = serde_json::Result<Map<String, Value>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            JsonSource::Mmap { reader, .. } => reader.next(),
+            JsonSource::Boxed(reader) => reader.next(),
+        }
+    }
+}
+
+pub struct NdJsonChunker<'a> {
+    reader: JsonSource,
     pub buffer: Vec<u8>,
     pub size: usize,
     pub ignore_embeddings: bool,
+    skip_malformed: bool,
+    pb: &'a ProgressBar,
+    /// The number of malformed records skipped so far, only ever incremented
+    /// when `skip_malformed` is set.
+    pub skipped: usize,
 }
 
-impl NdJsonChunker {
-    pub fn new(path: PathBuf, size: usize, ignore_embeddings: bool) -> Self {
-        let reader = if path == Path::new("-") {
-            Box::new(io::stdin()) as Box<dyn Read>
+impl<'a> NdJsonChunker<'a> {
+    pub fn new(
+        path: PathBuf,
+        size: usize,
+        ignore_embeddings: bool,
+        compression: InputCompression,
+        skip_malformed: bool,
+        pb: &'a ProgressBar,
+    ) -> Self {
+        // A compressed file must be decoded through a streaming reader, so only a
+        // plain file on disk takes the memory-mapped fast path.
+        let reader = if path == Path::new("-") || compression != InputCompression::None {
+            let reader = crate::compression::open(&path, compression);
+            let reader = BufReader::new(reader);
+            JsonSource::Boxed(Deserializer::from_reader(reader).into_iter())
         } else {
-            Box::new(File::open(path).unwrap())
+            let file = File::open(path).unwrap();
+            // mmap(2) rejects zero-length mappings, so an empty file falls back to
+            // the boxed reader path the same as stdin/compressed input.
+            if file.metadata().unwrap().len() == 0 {
+                let reader: Box<dyn Read> = Box::new(file);
+                let reader = BufReader::new(reader);
+                JsonSource::Boxed(Deserializer::from_reader(reader).into_iter())
+            } else {
+                let mmap = unsafe { Mmap::map(&file).unwrap() };
+                // SAFETY: `_mmap` is declared after `reader` in this variant, so it is
+                // dropped after the reader that borrows from it (fields are dropped in
+                // declaration order), keeping the mapped bytes valid for the reader's
+                // whole lifetime even though `mmap` itself is moved into the struct.
+                let bytes: &'static [u8] = unsafe { mem::transmute(mmap.as_ref()) };
+                let reader = Deserializer::from_slice(bytes).into_iter();
+                JsonSource::Mmap { reader, _mmap: mmap }
+            }
         };
-        let reader = BufReader::new(reader);
-        Self {
-            reader: Deserializer::from_reader(reader).into_iter(),
-            buffer: Vec::new(),
-            size,
-            ignore_embeddings,
-        }
+        Self { reader, buffer: Vec::new(), size, ignore_embeddings, skip_malformed, pb, skipped: 0 }
     }
 }
 
-impl Iterator for NdJsonChunker {
-    type Item = Vec<u8>;
+impl<'a> Iterator for NdJsonChunker<'a> {
+    type Item = anyhow::Result<Vec<u8>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        for result in self.reader.by_ref() {
-            let mut object = result.unwrap();
+        while let Some(result) = self.reader.next() {
+            let mut object = match result {
+                Ok(object) => object,
+                Err(e) => {
+                    let line = e.line();
+                    let snippet = truncate_snippet(&e.to_string(), 100);
+                    if self.skip_malformed {
+                        self.skipped += 1;
+                        self.pb.println(format!("Skipping malformed NDJSON record at line {line}: {snippet}"));
+                        continue;
+                    }
+                    return Some(Err(anyhow::anyhow!(
+                        "malformed NDJSON record at line {line}: {snippet}"
+                    )));
+                }
+            };
 
             if self.ignore_embeddings {
                 object.remove("_vectors");
@@ -52,7 +115,7 @@ impl Iterator for NdJsonChunker {
                 let buffer = mem::take(&mut self.buffer);
                 // Insert the record but after we sent the buffer
                 to_writer(&mut self.buffer, &object).unwrap();
-                return Some(buffer);
+                return Some(Ok(buffer));
             } else {
                 // Insert the record
                 to_writer(&mut self.buffer, &object).unwrap();
@@ -61,7 +124,7 @@ impl Iterator for NdJsonChunker {
         if self.buffer.is_empty() {
             None
         } else {
-            Some(mem::take(&mut self.buffer))
+            Some(Ok(mem::take(&mut self.buffer)))
         }
     }
 }
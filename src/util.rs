@@ -0,0 +1,9 @@
+/// Truncates `s` to at most `max_len` characters, appending an ellipsis when
+/// something was cut off, so a malformed-record error doesn't dump an entire
+/// multi-megabyte line to the terminal.
+pub(crate) fn truncate_snippet(s: &str, max_len: usize) -> String {
+    match s.char_indices().nth(max_len) {
+        Some((end, _)) => format!("{}...", &s[..end]),
+        None => s.to_owned(),
+    }
+}